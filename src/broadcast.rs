@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    pin::Pin,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use hyper::body::Bytes;
+use tokio::{
+    process::Command,
+    sync::{mpsc, Notify, OwnedSemaphorePermit, Semaphore},
+};
+
+use crate::{format::OutputFormat, stream::Stream};
+
+/// Identifies one ffmpeg transcode: a channel at a minimum resolution,
+/// rendered into one output format. Requests that share a key share the
+/// same ffmpeg process and `Stream` instead of each spawning their own.
+pub type BroadcastKey = (String, u32, OutputFormat);
+
+/// One ffmpeg child process plus the `Stream` decoding its output, shared
+/// across every viewer currently watching this channel/resolution/format.
+#[derive(Debug)]
+pub struct SharedBroadcast {
+    child: Mutex<tokio::process::Child>,
+    stream: Mutex<Stream>,
+    changed: Notify,
+    subscriber_count: Mutex<usize>,
+    // false once the pump has seen ffmpeg exit or this broadcast has been
+    // killed off; checked under the registry's `broadcasts` lock so a
+    // subscriber can never be handed a broadcast no one is pumping anymore
+    alive: AtomicBool,
+}
+
+impl SharedBroadcast {
+    fn new(child: tokio::process::Child, stream: Stream) -> SharedBroadcast {
+        SharedBroadcast {
+            child: Mutex::new(child),
+            stream: Mutex::new(stream),
+            changed: Notify::new(),
+            subscriber_count: Mutex::new(0),
+            alive: AtomicBool::new(true),
+        }
+    }
+
+    fn write(&self, data: &[u8]) -> eyre::Result<()> {
+        self.stream.lock().unwrap().write(data)?;
+        self.changed.notify_waiters();
+        Ok(())
+    }
+
+    /// Waits until the stream has data after `offset`, then returns it
+    /// along with the subscriber's new cursor.
+    async fn read_after(&self, offset: usize) -> (usize, Vec<u8>) {
+        loop {
+            // `notified()` only registers as a waiter once it's polled, so
+            // pin it and `enable()` it before the check below: otherwise a
+            // `write()` + `notify_waiters()` landing between the check and
+            // the first poll of `notified.await` would be missed.
+            let notified = self.changed.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            {
+                let stream = self.stream.lock().unwrap();
+                let (new_offset, data) = stream.read_after(offset);
+                if new_offset > offset {
+                    return (new_offset, data.to_vec());
+                }
+            }
+            notified.await;
+        }
+    }
+}
+
+/// A single viewer's handle onto a `SharedBroadcast`, with its own
+/// independent read cursor. Dropping the last subscription for a
+/// broadcast kills its ffmpeg child and removes it from the registry.
+#[derive(Debug)]
+pub struct Subscription {
+    key: BroadcastKey,
+    registry: Arc<Registry>,
+    broadcast: Arc<SharedBroadcast>,
+    offset: usize,
+}
+
+impl Subscription {
+    /// Waits for the next chunk of encoded output past this subscriber's
+    /// cursor. The very first call (`offset == 0`) resolves to the
+    /// preamble; the calls after it continue from the oldest frame still
+    /// buffered, so late joiners start at a valid point in the stream
+    /// rather than the beginning of the broadcast.
+    pub async fn next(&mut self) -> Vec<u8> {
+        let (new_offset, data) = self.broadcast.read_after(self.offset).await;
+        self.offset = new_offset;
+        data
+    }
+
+    /// Turns this subscription into a `futures::Stream` of encoded chunks,
+    /// so it can be handed straight to `hyper::Body::wrap_stream`. Up to
+    /// `buffer_budget` bytes of decoded-but-unsent output may be
+    /// prefetched ahead of a slow consumer, decoupling how fast we pull
+    /// frames off the shared broadcast from how fast the client drains
+    /// them.
+    pub fn into_stream(self, buffer_budget: u32) -> SubscriptionStream {
+        SubscriptionStream::new(self, buffer_budget)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // Lock `broadcasts` before `subscriber_count`, same order as
+        // `Registry::subscribe`, so a concurrent last-drop and new-subscribe
+        // can't deadlock on each other, and so the decrement-to-zero and the
+        // get-or-insert are serialized against each other instead of racing
+        // (otherwise a fresh subscriber could be handed a broadcast we're
+        // about to remove and kill).
+        let mut broadcasts = self.registry.broadcasts.lock().unwrap();
+        let mut count = self.broadcast.subscriber_count.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.broadcast.alive.store(false, Ordering::Release);
+            // only remove the map entry if it's still *this* broadcast: a
+            // concurrent pump exit (or another subscriber racing us) may
+            // already have replaced it with a fresh one under the same key,
+            // and removing that would orphan a live broadcast.
+            if broadcasts.get(&self.key).is_some_and(|b| Arc::ptr_eq(b, &self.broadcast)) {
+                broadcasts.remove(&self.key);
+            }
+            drop(count);
+            drop(broadcasts);
+            let mut child = self.broadcast.child.lock().unwrap();
+            let _ = child.start_kill();
+        }
+    }
+}
+
+/// Tracks the one `SharedBroadcast` per (channel, resolution, format), so
+/// concurrent viewers of the same stream reuse a single ffmpeg transcode.
+#[derive(Debug, Default)]
+pub struct Registry {
+    broadcasts: Mutex<HashMap<BroadcastKey, Arc<SharedBroadcast>>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Attaches a new viewer to the broadcast for `key`, spawning ffmpeg
+    /// and starting the pump task if no one else is watching it yet.
+    pub fn subscribe(self: &Arc<Self>, key: BroadcastKey, playlist_url: &str)
+            -> eyre::Result<Subscription> {
+        let mut broadcasts = self.broadcasts.lock().unwrap();
+        // a present-but-dead entry (pump already saw ffmpeg exit, but
+        // hasn't removed it yet, or another subscriber's drop raced us)
+        // is treated the same as absent: spawn a fresh one rather than
+        // handing out a broadcast nothing is pumping anymore.
+        let broadcast = match broadcasts.get(&key).filter(|b| b.alive.load(Ordering::Acquire)) {
+            Some(broadcast) => broadcast.clone(),
+            None => {
+                let (_, _, format) = &key;
+                let mut child = spawn_ffmpeg(playlist_url, *format)?;
+                let child_output = child.stdout.take().unwrap();
+                let stream = Stream::new(format.splitter()?);
+                let broadcast = Arc::new(SharedBroadcast::new(child, stream));
+                spawn_pump(self.clone(), key.clone(), broadcast.clone(), child_output);
+                broadcasts.insert(key.clone(), broadcast.clone());
+                broadcast
+            }
+        };
+        *broadcast.subscriber_count.lock().unwrap() += 1;
+
+        Ok(Subscription {
+            key,
+            registry: self.clone(),
+            broadcast,
+            offset: 0,
+        })
+    }
+}
+
+fn spawn_ffmpeg(playlist_url: &str, format: OutputFormat) -> eyre::Result<tokio::process::Child> {
+    Ok(Command::new("ffmpeg")
+        .args(&["-loglevel", "error", "-i", playlist_url])
+        .args(format.ffmpeg_args())
+        .arg("-")
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?)
+}
+
+/// Reads ffmpeg's stdout into `broadcast` until it exits or a decode error
+/// occurs, then removes the broadcast from the registry so the next
+/// subscriber spawns a fresh ffmpeg instead of joining a dead one.
+fn spawn_pump(registry: Arc<Registry>, key: BroadcastKey, broadcast: Arc<SharedBroadcast>,
+        mut child_output: tokio::process::ChildStdout) {
+    tokio::task::spawn(async move {
+        use tokio::io::AsyncReadExt;
+        let mut buf = [0u8; 8*1024];
+        loop {
+            let n = match child_output.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => {
+                    tracing::warn!(%e, "ffmpeg read failed");
+                    break;
+                }
+            };
+            if let Err(e) = broadcast.write(&buf[..n]) {
+                tracing::warn!(%e, "failed to decode ffmpeg output");
+                break;
+            }
+        }
+        tracing::debug!(?key, "ffmpeg pump exiting");
+        // mark-dead and remove happen under the same lock acquisition as
+        // subscribe()'s get-or-insert, and guard the remove by identity, so
+        // a subscriber that raced in with a replacement broadcast never has
+        // it evicted here.
+        let mut broadcasts = registry.broadcasts.lock().unwrap();
+        broadcast.alive.store(false, Ordering::Release);
+        if broadcasts.get(&key).is_some_and(|b| Arc::ptr_eq(b, &broadcast)) {
+            broadcasts.remove(&key);
+        }
+    });
+}
+
+/// A `futures::Stream` of encoded output chunks, backed by a `Subscription`.
+/// A background task pulls frames off the shared broadcast and forwards
+/// them through a channel bounded by `buffer_budget` bytes, so a consumer
+/// that's slow to poll this stream doesn't make the pull side race
+/// arbitrarily far ahead. Dropping the stream aborts that task, which
+/// drops the `Subscription` and, if it was the last one, kills the
+/// underlying ffmpeg process.
+#[derive(Debug)]
+pub struct SubscriptionStream {
+    receiver: mpsc::UnboundedReceiver<(Bytes, OwnedSemaphorePermit)>,
+    puller: tokio::task::JoinHandle<()>,
+}
+
+impl SubscriptionStream {
+    fn new(subscription: Subscription, buffer_budget: u32) -> SubscriptionStream {
+        let in_flight = Arc::new(Semaphore::new(buffer_budget as usize));
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let puller = tokio::task::spawn(
+            pull_subscription(subscription, in_flight, buffer_budget, sender));
+
+        SubscriptionStream { receiver, puller }
+    }
+}
+
+async fn pull_subscription(mut subscription: Subscription, in_flight: Arc<Semaphore>,
+        buffer_budget: u32, sender: mpsc::UnboundedSender<(Bytes, OwnedSemaphorePermit)>) {
+    loop {
+        let data = subscription.next().await;
+        if data.is_empty() {
+            continue;
+        }
+
+        let permits = (data.len() as u32).min(buffer_budget);
+        let permit = match in_flight.clone().acquire_many_owned(permits).await {
+            Ok(permit) => permit,
+            Err(_) => break,
+        };
+        if sender.send((Bytes::from(data), permit)).is_err() {
+            break;
+        }
+    }
+}
+
+impl futures::Stream for SubscriptionStream {
+    type Item = Result<Bytes, Infallible>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.receiver.poll_recv(cx) {
+            Poll::Ready(Some((bytes, permit))) => {
+                drop(permit);
+                Poll::Ready(Some(Ok(bytes)))
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for SubscriptionStream {
+    fn drop(&mut self) {
+        self.puller.abort();
+    }
+}
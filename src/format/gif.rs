@@ -0,0 +1,33 @@
+use crate::stream::{FrameEvent, FrameSplitter};
+
+#[derive(Debug)]
+pub struct GifSplitter {
+    decoder: gif::StreamingDecoder,
+}
+
+impl GifSplitter {
+    pub fn new() -> GifSplitter {
+        GifSplitter {
+            decoder: gif::StreamingDecoder::new(),
+        }
+    }
+}
+
+impl FrameSplitter for GifSplitter {
+    fn update(&mut self, data: &[u8]) -> eyre::Result<(usize, FrameEvent)> {
+        let (n, decoded) = self.decoder.update(data)?;
+        use gif::Decoded::*;
+        let event = match decoded {
+            Nothing => FrameEvent::Nothing,
+            BlockStart(..)
+                | SubBlockFinished(..)
+                | BlockFinished(..)
+                | Frame(..)
+                | Data(..) => FrameEvent::Frame,
+            DataEnd => FrameEvent::FrameEnd,
+            _ => FrameEvent::Preamble,
+        };
+
+        Ok((n, event))
+    }
+}
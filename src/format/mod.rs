@@ -0,0 +1,100 @@
+use std::str::FromStr;
+
+use crate::stream::FrameSplitter;
+
+mod gif;
+mod mp4;
+
+pub use gif::GifSplitter;
+pub use mp4::Mp4Splitter;
+
+/// The encoded output a viewer receives. Selected per-request via an extra
+/// URL path segment or an `Accept` header, falling back to `Args::default_format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputFormat {
+    Gif,
+    Webp,
+    Apng,
+    Mp4,
+}
+
+impl OutputFormat {
+    /// ffmpeg output args for this format, to be appended after `-i <url>`.
+    pub fn ffmpeg_args(&self) -> &'static [&'static str] {
+        match self {
+            OutputFormat::Gif => &["-f", "gif", "-loop", "-1"],
+            OutputFormat::Webp => &["-f", "webp", "-loop", "0"],
+            OutputFormat::Apng => &["-f", "apng", "-plays", "0"],
+            OutputFormat::Mp4 => &[
+                "-movflags",
+                "empty_moov+frag_every_frame+separate_moof+omit_tfhd_offset",
+                "-f", "mp4",
+                "-c:v", "copy",
+            ],
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Gif => "image/gif",
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Apng => "image/apng",
+            OutputFormat::Mp4 => "video/mp4",
+        }
+    }
+
+    /// Whether `splitter()` can actually produce a splitter for this format
+    /// yet. Format selection (path segment, Accept header) only ever
+    /// resolves to implemented formats, falling back to
+    /// `Args::default_format` otherwise, so picking an unimplemented format
+    /// never turns into a request failure.
+    pub fn is_implemented(&self) -> bool {
+        matches!(self, OutputFormat::Gif | OutputFormat::Mp4)
+    }
+
+    /// A fresh frame splitter for this format's ffmpeg output.
+    pub fn splitter(&self) -> eyre::Result<Box<dyn FrameSplitter + Send>> {
+        match self {
+            OutputFormat::Gif => Ok(Box::new(GifSplitter::new())),
+            OutputFormat::Mp4 => Ok(Box::new(Mp4Splitter::new())),
+            other => Err(eyre::eyre!("output format {:?} is not implemented yet", other)),
+        }
+    }
+
+    /// Parses a format out of a trailing URL path segment, e.g. `/channel/0/mp4`.
+    /// Formats that are not implemented yield `None`.
+    pub fn from_path_segment(segment: &str) -> Option<OutputFormat> {
+        OutputFormat::from_str(segment).ok().filter(OutputFormat::is_implemented)
+    }
+
+    /// Returns the first implemented format listed in an `Accept` header
+    /// value, in the order the entries appear; `q` parameters are ignored.
+    pub fn from_accept_header(accept: &str) -> Option<OutputFormat> {
+        accept.split(',')
+            .map(|entry| entry.split(';').next().unwrap_or("").trim())
+            .find_map(|mime| {
+                let format = match mime {
+                    "image/gif" => OutputFormat::Gif,
+                    "image/webp" => OutputFormat::Webp,
+                    "image/apng" | "image/png" => OutputFormat::Apng,
+                    "video/mp4" => OutputFormat::Mp4,
+                    _ => return None,
+                };
+                format.is_implemented().then_some(format)
+            })
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> eyre::Result<OutputFormat> {
+        match s {
+            "gif" => Ok(OutputFormat::Gif),
+            "webp" => Ok(OutputFormat::Webp),
+            "apng" => Ok(OutputFormat::Apng),
+            "mp4" => Ok(OutputFormat::Mp4),
+            _ => Err(eyre::eyre!("unknown output format {:?}", s)),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::stream::{FrameEvent, FrameSplitter};
+
+/// Splits ffmpeg's fragmented-MP4 output (`empty_moov+frag_every_frame
+/// +separate_moof+omit_tfhd_offset`) into complete `moof`+`mdat` pairs by
+/// walking the top-level ISO-BMFF box structure. The leading `ftyp`+`moov`
+/// run becomes the preamble; every top-level `moof` starts a new frame,
+/// finishing whichever frame was previously open.
+#[derive(Debug)]
+pub struct Mp4Splitter {
+    in_preamble: bool,
+    // bytes left in the top-level box currently being streamed through;
+    // 0 means we're at a box boundary and need to read a header
+    remaining_in_box: u64,
+    // set once we've peeked a new top-level `moof` header while a frame was
+    // already open: the previous frame must be flushed (with zero extra
+    // bytes) before any of this box's bytes are classified
+    pending_boundary: Option<(usize, u64)>,
+}
+
+impl Mp4Splitter {
+    pub fn new() -> Mp4Splitter {
+        Mp4Splitter {
+            in_preamble: true,
+            remaining_in_box: 0,
+            pending_boundary: None,
+        }
+    }
+}
+
+impl FrameSplitter for Mp4Splitter {
+    fn update(&mut self, data: &[u8]) -> eyre::Result<(usize, FrameEvent)> {
+        // the previous frame was already finished (with zero bytes) at the
+        // point we first recognized this moof; what's left is to start
+        // tracking its header+body as ordinary frame bytes below, not to
+        // finish (a now-nonexistent) frame a second time.
+        if let Some((header_len, body_remaining)) = self.pending_boundary.take() {
+            self.remaining_in_box = header_len as u64 + body_remaining;
+        }
+
+        if data.is_empty() {
+            return Ok((0, FrameEvent::Nothing));
+        }
+
+        if self.remaining_in_box > 0 {
+            let n = self.remaining_in_box.min(data.len() as u64) as usize;
+            self.remaining_in_box -= n as u64;
+            let event = if self.in_preamble { FrameEvent::Preamble } else { FrameEvent::Frame };
+            return Ok((n, event));
+        }
+
+        // at a top-level box boundary: need a full header before we can
+        // say anything. headers can straddle read buffers, so bail out
+        // with Nothing (leaving it in Stream's `undecoded` buffer) until
+        // enough bytes have accumulated.
+        if data.len() < 8 {
+            return Ok((0, FrameEvent::Nothing));
+        }
+
+        let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as u64;
+        let box_type = &data[4..8];
+        let (header_len, box_size) = if size == 1 {
+            if data.len() < 16 {
+                return Ok((0, FrameEvent::Nothing));
+            }
+            let mut extended = [0u8; 8];
+            extended.copy_from_slice(&data[8..16]);
+            (16usize, u64::from_be_bytes(extended))
+        } else if size == 0 {
+            return Err(eyre::eyre!(
+                "mp4 box with unbounded (size 0) is not supported in a fragmented stream"));
+        } else {
+            (8usize, size)
+        };
+
+        if box_size < header_len as u64 {
+            return Err(eyre::eyre!("mp4 box size {} smaller than its own header", box_size));
+        }
+        let body_len = box_size - header_len as u64;
+
+        if box_type == b"moof" {
+            if self.in_preamble {
+                self.in_preamble = false;
+                self.remaining_in_box = body_len;
+                return Ok((header_len, FrameEvent::Frame));
+            }
+
+            tracing::trace!("moof starts a new frame, finishing the previous one");
+            self.pending_boundary = Some((header_len, body_len));
+            return Ok((0, FrameEvent::FrameEnd));
+        }
+
+        let event = if self.in_preamble { FrameEvent::Preamble } else { FrameEvent::Frame };
+        self.remaining_in_box = body_len;
+        Ok((header_len, event))
+    }
+}
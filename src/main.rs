@@ -3,15 +3,18 @@ use {
         str::FromStr,
         io,
         net,
+        sync::Arc,
     },
 
     url::Url,
     tracing::{debug, instrument},
     spandoc::{spandoc},
 
-    stream::Stream,
+    format::OutputFormat,
 };
 
+mod broadcast;
+mod format;
 mod stream;
 
 #[instrument]
@@ -47,6 +50,19 @@ struct Args {
 
     #[structopt(long = "listen-addr", name = "ADDR", default_value = "127.0.0.1:8080")]
     listen_addr: net::SocketAddr,
+
+    #[structopt(long = "default-format", name = "FORMAT", default_value = "gif")]
+    default_format: OutputFormat,
+
+    /// how many bytes of decoded-but-unsent output a single slow client is
+    /// allowed to have in flight before we stop pulling more on its behalf
+    #[structopt(long = "subscriber-buffer-bytes", name = "BYTES", default_value = "1048576")]
+    subscriber_buffer_bytes: u32,
+}
+
+struct AppState {
+    args: Args,
+    registry: Arc<broadcast::Registry>,
 }
 
 #[paw::main]
@@ -74,27 +90,41 @@ fn main(args: Args) -> eyre::Result<()> {
     /// Setting up default tracing subscriber
     tracing::subscriber::set_global_default(subscriber)?;
 
-    run_hyper_service(&args.listen_addr)?;
+    if !args.default_format.is_implemented() {
+        eyre::bail!("--default-format {:?} is not implemented yet", args.default_format);
+    }
+
+    let state = Arc::new(AppState {
+        args,
+        registry: Arc::new(broadcast::Registry::new()),
+    });
+    run_hyper_service(state)?;
 
     Ok(())
 }
 
-fn run_hyper_service(listen_addr: &net::SocketAddr) -> eyre::Result<()> {
+fn run_hyper_service(state: Arc<AppState>) -> eyre::Result<()> {
     use hyper::{Body, Error, Request, Response};
     use hyper::service::{make_service_fn, service_fn};
 
-    let make_svc = make_service_fn(|_| async {
-        Ok::<_, Error>(service_fn(|req: Request<Body>| async move {
-            Ok::<_, Error>(match start_stream(req).await {
-                Ok(response) => response,
-                Err(e) => Response::new(Body::from(e.to_string()))
-            })
-        }))
+    let make_svc = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Error>(service_fn(move |req: Request<Body>| {
+                let state = state.clone();
+                async move {
+                    Ok::<_, Error>(match start_stream(&state, req).await {
+                        Ok(response) => response,
+                        Err(e) => Response::new(Body::from(e.to_string()))
+                    })
+                }
+            }))
+        }
     });
 
     let mut runtime = tokio::runtime::Runtime::new()?;
     runtime.block_on(async {
-        hyper::Server::try_bind(listen_addr)?
+        hyper::Server::try_bind(&state.args.listen_addr)?
             .serve(make_svc).await?;
         Ok::<_, eyre::ErrReport>(())
     })?;
@@ -102,80 +132,29 @@ fn run_hyper_service(listen_addr: &net::SocketAddr) -> eyre::Result<()> {
     Ok(())
 }
 
-async fn start_stream(req: hyper::Request<hyper::Body>)
+async fn start_stream(state: &AppState, req: hyper::Request<hyper::Body>)
         -> eyre::Result<hyper::Response<hyper::Body>> {
-    use hyper::{Body, body::Bytes, Response};
+    use hyper::{Body, Response};
     let mut segments = req.uri().path().split('/').skip(1);
     let channel_name = segments.next().filter(|x| !x.is_empty())
         .unwrap_or("hungry");
     let minimum_resolution = segments.next().filter(|x| !x.is_empty())
         .and_then(|s| u32::from_str(s).ok()).unwrap_or(0);
+    let format = segments.next().filter(|x| !x.is_empty())
+        .and_then(OutputFormat::from_path_segment)
+        .or_else(|| req.headers().get(hyper::header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .and_then(OutputFormat::from_accept_header))
+        .unwrap_or(state.args.default_format);
     let playlist_url =
         get_variant_playlist_url(channel_name, minimum_resolution).await?;
 
-    use tokio::process::Command;
-    use std::process::Stdio;
-    let mut child = Command::new("ffmpeg")
-        .args(&[
-            "-loglevel", "error",
-            "-i", &playlist_url,
-            "-f", "gif",
-            "-loop", "-1",
-            "-"])
-        .stdout(Stdio::piped())
-        .kill_on_drop(true)
-        .spawn()?;
-
-    let (mut sender, body) = Body::channel();
-
-    let mut child_output = child.stdout.take().unwrap();
-
-    let copy_from_ffmpeg_to_client = async move {
-        use tokio::io::AsyncReadExt;
-        let mut buf = [0u8; 8*1024];
-
-        let mut stream = Stream::new();
-        let mut offset = 0usize;
-
-        loop {
-            let n = child_output.read(&mut buf).await?;
-            if n <= 0 {
-                continue;
-            }
-            let buf = &buf[..n];
-
-            stream.write(buf)?;
-
-            loop {
-                let (new_offset, data) = stream.read_after(offset);
-                if offset >= new_offset {
-                    break;
-                }
-                offset = new_offset;
-
-                let bytes = Bytes::copy_from_slice(data);
-                sender.send_data(bytes).await?;
-            }
-        }
-        Ok::<_, eyre::ErrReport>(())
-    };
-
-    tokio::task::spawn(async move {
-        tokio::pin!(copy_from_ffmpeg_to_client);
-        tokio::select! {
-            _ = &mut copy_from_ffmpeg_to_client => {
-                child.kill()?;
-                child.await?;
-            },
-            _ = &mut child => {
-                copy_from_ffmpeg_to_client.await?;
-            },
-        }
-        Ok::<_, eyre::ErrReport>(())
-    });
+    let key = (channel_name.to_string(), minimum_resolution, format);
+    let subscription = state.registry.subscribe(key, &playlist_url)?;
+    let body = Body::wrap_stream(subscription.into_stream(state.args.subscriber_buffer_bytes));
 
     let response = Response::builder()
-       .header("Content-Type", "image/gif")
+       .header("Content-Type", format.content_type())
        .body(body)?;
     Ok(response)
 }
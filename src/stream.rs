@@ -9,25 +9,47 @@ struct Blob {
     data: Vec<u8>
 }
 
+/// What a chunk just consumed by a `FrameSplitter` belongs to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameEvent {
+    /// the splitter needs more data before it can classify anything new
+    Nothing,
+    /// belongs before the first frame (container headers, palette, etc.)
+    Preamble,
+    /// belongs to the frame currently being assembled
+    Frame,
+    /// completes the frame currently being assembled
+    FrameEnd,
+}
+
+/// Incrementally classifies a raw encoder byte stream into preamble and
+/// frame boundaries, so `Stream` can track frame offsets without knowing
+/// anything about the underlying container format.
+pub trait FrameSplitter: std::fmt::Debug {
+    /// Feed more bytes from the encoder. Returns how many of `data` were
+    /// consumed and what they belong to.
+    fn update(&mut self, data: &[u8]) -> eyre::Result<(usize, FrameEvent)>;
+}
+
 #[derive(Debug)]
 pub struct Stream {
+    splitter: Box<dyn FrameSplitter + Send>,
     preamble: Vec<u8>,
     frames: VecDeque<Blob>,
     offset: usize,
     partial_frame: Vec<u8>,
-    decoder: gif::StreamingDecoder,
     undecoded: Vec<u8>,
     decoded: Vec<u8>,
 }
 
 impl Stream {
-    pub fn new() -> Stream {
+    pub fn new(splitter: Box<dyn FrameSplitter + Send>) -> Stream {
         Stream {
+            splitter,
             preamble: Vec::new(),
             frames: VecDeque::new(),
             offset: 0,
             partial_frame: Vec::new(),
-            decoder: gif::StreamingDecoder::new(),
             undecoded: Vec::new(),
             decoded: Vec::new(),
         }
@@ -61,10 +83,13 @@ impl Stream {
             return (end, &frame.data[offset - frame.offset..]);
         }
 
-        // didn't find any frame with offset >= frame.offset,
-        // just ship earlieset available frame
+        // didn't find any frame with offset >= frame.offset: this reader
+        // fell behind the 1 MiB eviction horizon and its cursor now points
+        // into frames we've already popped. fast-forward it to the
+        // earliest surviving frame instead of erroring.
         if let Some(frame) = self.frames.front() {
             let end = frame.offset + frame.data.len();
+            tracing::info!(%offset, resynced_to = %frame.offset, "resynced slow reader");
             return (end, &frame.data[..]);
         }
 
@@ -91,12 +116,13 @@ impl Stream {
         } else {
             let mut undecoded = mem::replace(&mut self.undecoded, Vec::new());
             undecoded.extend_from_slice(data);
-            let data = &undecoded[..];
+            let mut data = &undecoded[..];
             loop {
                 let n = self.consume(data)?;
                 if n == 0 {
                     break;
                 }
+                data = &data[n..];
             }
             let len = data.len();
             if len > 0 {
@@ -110,26 +136,22 @@ impl Stream {
     fn consume(&mut self, mut data: &[u8]) -> eyre::Result<usize> {
         let mut consumed = 0;
         loop {
-            let (n, decoded) = self.decoder.update(data)?;
+            let (n, event) = self.splitter.update(data)?;
             consumed += n;
-            use gif::Decoded::*;
-            match decoded {
+            use FrameEvent::*;
+            match event {
                 Nothing => {
                     self.decoded.extend_from_slice(&data[..n]);
                     break;
                 }
-                BlockStart(..)
-                    | SubBlockFinished(..)
-                    | BlockFinished(..)
-                    | Frame(..)
-                    | Data(..) => {
+                Frame => {
                     self.add_to_frame(&data[..n]);
                 },
-                DataEnd => {
+                FrameEnd => {
                     self.add_to_frame(&data[..n]);
                     self.finish_frame();
                 },
-                _ => {
+                Preamble => {
                     self.add_to_preamble(&data[..n]);
                 }
             }